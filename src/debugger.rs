@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use chip8_base::{Display, Interpreter, Keys};
+
+use crate::interpreter::VMState;
+
+/// What the debugger should do the next time its `step` is invoked.
+enum Mode {
+    /// Prompt the REPL before executing anything.
+    Halted,
+    /// Run freely, only stopping to prompt once a breakpoint is hit.
+    Continuing,
+}
+
+/// Wraps a `VMState`, pausing before each `step()` for an interactive
+/// monitor-style REPL: set/clear PC breakpoints, single-step (optionally
+/// with a repeat count), continue, dump registers/memory, and
+/// disassemble the instruction at the current PC.
+pub struct Debugger {
+    vm: VMState,
+    breakpoints: HashSet<u16>,
+    mode: Mode,
+    /// Print every executed instruction instead of halting for input.
+    trace_only: bool,
+}
+
+enum Action {
+    Step(u32),
+    Continue,
+}
+
+impl Debugger {
+    pub fn new(vm: VMState, trace_only: bool) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: HashSet::new(),
+            mode: Mode::Halted,
+            trace_only,
+        }
+    }
+
+    fn print_regs(&self) {
+        for (row, chunk) in self.vm.registers().chunks(4).enumerate() {
+            for (i, v) in chunk.iter().enumerate() {
+                print!("V{:X}={:02X} ", row * 4 + i, v);
+            }
+            println!();
+        }
+        println!(
+            "I={:04X}  SP={:02X}  PC={:04X}",
+            self.vm.index(),
+            self.vm.sp(),
+            self.vm.pc()
+        );
+    }
+
+    fn hexdump(&self, start: u16, len: u16) {
+        let len = len.min(4096u16.saturating_sub(start));
+        for (row, chunk) in self.vm.memory_range(start, len).chunks(16).enumerate() {
+            print!("{:04X}: ", start as usize + row * 16);
+            for b in chunk {
+                print!("{:02X} ", b);
+            }
+            println!();
+        }
+    }
+
+    /// Decode the instruction at `pc` into a short mnemonic, reusing the
+    /// same nibble/address extraction the interpreter uses to execute it.
+    fn disassemble(&self, pc: u16) -> String {
+        let bytes = self.vm.memory_range(pc, 2);
+        let (n0, n1, n2, n3) = VMState::extract_nibbles((bytes[0], bytes[1]));
+        let nnn = VMState::extract_12_bits(n1, n2, n3);
+        let kk = (n2 << 4) | n3;
+        match (n0, n1, n2, n3) {
+            (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+            (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+            (0x1, ..) => format!("JP {:#05X}", nnn),
+            (0x2, ..) => format!("CALL {:#05X}", nnn),
+            (0x3, x, ..) => format!("SE V{:X}, {:#04X}", x, kk),
+            (0x4, x, ..) => format!("SNE V{:X}, {:#04X}", x, kk),
+            (0x5, x, y, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+            (0x6, x, ..) => format!("LD V{:X}, {:#04X}", x, kk),
+            (0x7, x, ..) => format!("ADD V{:X}, {:#04X}", x, kk),
+            (0x8, x, y, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+            (0x8, x, y, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+            (0x8, x, y, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+            (0x8, x, y, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+            (0x8, x, y, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+            (0x8, x, y, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+            (0x8, x, _, 0x6) => format!("SHR V{:X}", x),
+            (0x8, x, y, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+            (0x8, x, _, 0xE) => format!("SHL V{:X}", x),
+            (0x9, x, y, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+            (0xA, ..) => format!("LD I, {:#05X}", nnn),
+            (0xB, ..) => format!("JP V0, {:#05X}", nnn),
+            (0xC, x, ..) => format!("RND V{:X}, {:#04X}", x, kk),
+            (0xD, x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            (0xE, x, 0x9, 0xE) => format!("SKP V{:X}", x),
+            (0xE, x, 0xA, 0x1) => format!("SKNP V{:X}", x),
+            (0xF, x, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+            (0xF, x, 0x0, 0xA) => format!("LD V{:X}, K", x),
+            (0xF, x, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+            (0xF, x, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+            (0xF, x, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+            (0xF, x, 0x2, 0x9) => format!("LD F, V{:X}", x),
+            (0xF, x, 0x3, 0x3) => format!("LD B, V{:X}", x),
+            (0xF, x, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+            (0xF, x, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+            _ => format!("??? {:02X}{:02X}", bytes[0], bytes[1]),
+        }
+    }
+
+    fn print_current_instruction(&self) {
+        println!("{:04X}: {}", self.vm.pc(), self.disassemble(self.vm.pc()));
+    }
+
+    /// Read and handle commands until one of them wants the machine to
+    /// actually run (`step`/`continue`).
+    fn repl(&mut self) -> Action {
+        let stdin = io::stdin();
+        loop {
+            self.print_current_instruction();
+            print!("dbg> ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return Action::Step(1);
+            }
+            let mut words = line.split_whitespace();
+            let cmd = match words.next() {
+                Some(c) => c,
+                None => continue,
+            };
+
+            match cmd {
+                "s" | "step" => {
+                    let count = words.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+                    return Action::Step(count);
+                }
+                "c" | "continue" => return Action::Continue,
+                "b" | "break" => {
+                    if let Some(addr) = words.next().and_then(|a| u16::from_str_radix(a.trim_start_matches("0x"), 16).ok()) {
+                        self.breakpoints.insert(addr);
+                        println!("Breakpoint set at {:04X}", addr);
+                    } else {
+                        println!("usage: break <hex addr>");
+                    }
+                }
+                "cb" | "clear" => {
+                    if let Some(addr) = words.next().and_then(|a| u16::from_str_radix(a.trim_start_matches("0x"), 16).ok()) {
+                        self.breakpoints.remove(&addr);
+                        println!("Breakpoint cleared at {:04X}", addr);
+                    } else {
+                        println!("usage: clear <hex addr>");
+                    }
+                }
+                "regs" => self.print_regs(),
+                "dump" => {
+                    let start = words.next().and_then(|a| u16::from_str_radix(a.trim_start_matches("0x"), 16).ok()).unwrap_or(0);
+                    let len = words.next().and_then(|a| a.parse().ok()).unwrap_or(16);
+                    self.hexdump(start, len);
+                }
+                "trace" => {
+                    self.trace_only = !self.trace_only;
+                    println!("trace_only = {}", self.trace_only);
+                }
+                "help" | "?" => println!(
+                    "commands: step [n] | continue | break <addr> | clear <addr> | regs | dump <addr> [len] | trace"
+                ),
+                other => println!("unknown command: {other}"),
+            }
+        }
+    }
+}
+
+impl Interpreter for Debugger {
+    fn step(&mut self, keys: &Keys) -> Option<Display> {
+        if self.trace_only {
+            self.print_current_instruction();
+            return self.vm.step(keys);
+        }
+
+        loop {
+            match self.mode {
+                Mode::Continuing => {
+                    if self.breakpoints.contains(&self.vm.pc()) {
+                        println!("Breakpoint hit at {:04X}", self.vm.pc());
+                        self.mode = Mode::Halted;
+                        continue;
+                    }
+                    return self.vm.step(keys);
+                }
+                Mode::Halted => match self.repl() {
+                    Action::Continue => {
+                        // Run the current instruction before checking
+                        // breakpoints again, so continuing from a
+                        // breakpoint doesn't re-hit it straight away.
+                        self.mode = Mode::Continuing;
+                        return self.vm.step(keys);
+                    }
+                    Action::Step(count) => {
+                        let mut last = None;
+                        for _ in 0..count.max(1) {
+                            last = self.vm.step(keys).or(last);
+                            if self.breakpoints.contains(&self.vm.pc()) {
+                                break;
+                            }
+                        }
+                        return last;
+                    }
+                },
+            }
+        }
+    }
+
+    fn speed(&self) -> std::time::Duration {
+        self.vm.speed()
+    }
+
+    fn buzzer_active(&self) -> bool {
+        self.vm.buzzer_active()
+    }
+}
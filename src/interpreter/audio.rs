@@ -0,0 +1,144 @@
+// Filtered square-wave buzzer. `VMState::buzzer_active` only ever gave
+// the host a bool to toggle on/off, which produces a harsh, clicky tone.
+// This generates actual PCM samples instead: a square wave at a
+// configurable pitch, passed through a one-pole high-pass then a
+// one-pole low-pass to round off the edges that cause the clicks and
+// high-pitched ringing raw square waves have.
+//
+// Samples are generated continuously by `VMState::step` (tied to the
+// emulator's own clock) into a bounded queue, rather than synthesized
+// on demand when a host happens to ask for them -- that keeps the
+// buzzer's phase in sync with emulated time regardless of whether or
+// how often anything actually drains the queue.
+
+/// Pitch and sample rate for the synthesized buzzer tone.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioConfig {
+    pub pitch_hz: f64,
+    pub sample_rate: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig { pitch_hz: 440.0, sample_rate: 44100 }
+    }
+}
+
+// Don't start emitting filtered audio until this many samples have run
+// through the filters, so the first note doesn't pop in immediately at
+// whatever phase the filters happen to be in.
+const STARTUP_SAMPLES: u64 = 64;
+
+// Low-pass cutoff, as a right-shift applied to the running error term.
+const LOWPASS_SHIFT: u8 = 3;
+
+// High-pass feedback coefficient, fixed-point with an implicit /256.
+const HIGHPASS_ALPHA_Q8: i32 = 250;
+
+// Cap on how many generated samples can sit in `queue` unconsumed, so a
+// host that never drains it doesn't leave the buzzer growing the queue
+// without bound; oldest samples are dropped first, same as
+// `RewindBuffer`'s oldest-frame eviction.
+const QUEUE_CAPACITY: usize = 8192;
+
+pub(crate) struct AudioSynth {
+    config: AudioConfig,
+    phase: f64,
+    prev_in: i16,
+    prev_hp_out: i16,
+    prev_lp_out: i16,
+    buffered: u64,
+    queue: std::collections::VecDeque<i16>,
+}
+
+impl AudioSynth {
+    pub(crate) fn new(config: AudioConfig) -> AudioSynth {
+        AudioSynth {
+            config,
+            phase: 0.0,
+            prev_in: 0,
+            prev_hp_out: 0,
+            prev_lp_out: 0,
+            buffered: 0,
+            queue: std::collections::VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn set_config(&mut self, config: AudioConfig) {
+        self.config = config;
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    /// Reset filter, phase and queued-sample state; called whenever the
+    /// buzzer restarts after being silent, so one note doesn't bleed
+    /// filter history (or stale queued samples) into the next.
+    pub(crate) fn reset(&mut self) {
+        self.phase = 0.0;
+        self.prev_in = 0;
+        self.prev_hp_out = 0;
+        self.prev_lp_out = 0;
+        self.buffered = 0;
+        self.queue.clear();
+    }
+
+    /// Whether enough samples have run through the filters since the
+    /// last `reset` that the output is past the startup ramp.
+    pub(crate) fn is_ramped(&self) -> bool {
+        self.buffered >= STARTUP_SAMPLES
+    }
+
+    fn raw_square(&mut self) -> i16 {
+        let period_samples = self.config.sample_rate as f64 / self.config.pitch_hz;
+        let half = period_samples / 2.0;
+        let value = if self.phase % period_samples < half { i16::MAX / 4 } else { -(i16::MAX / 4) };
+        self.phase += 1.0;
+        if self.phase >= period_samples {
+            self.phase %= period_samples;
+        }
+        value
+    }
+
+    fn highpass(&mut self, input: i16) -> i16 {
+        let feedback = (HIGHPASS_ALPHA_Q8 as i64 * self.prev_hp_out as i64) >> 8;
+        let out = (input as i64 - self.prev_in as i64 + feedback) as i16;
+        self.prev_in = input;
+        self.prev_hp_out = out;
+        out
+    }
+
+    fn lowpass(&mut self, input: i16) -> i16 {
+        let out = self.prev_lp_out + ((input - self.prev_lp_out) >> LOWPASS_SHIFT);
+        self.prev_lp_out = out;
+        out
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let raw = self.raw_square();
+        let high_passed = self.highpass(raw);
+        let filtered = self.lowpass(high_passed);
+        self.buffered += 1;
+        if self.buffered < STARTUP_SAMPLES { 0 } else { filtered }
+    }
+
+    /// Synthesize `n` more filtered samples onto the pending queue,
+    /// evicting the oldest queued sample first once `QUEUE_CAPACITY` is
+    /// reached.
+    pub(crate) fn generate(&mut self, n: usize) {
+        for _ in 0..n {
+            let sample = self.next_sample();
+            if self.queue.len() >= QUEUE_CAPACITY {
+                self.queue.pop_front();
+            }
+            self.queue.push_back(sample);
+        }
+    }
+
+    /// Pull up to `n` queued samples for the host audio layer, oldest
+    /// first, padding with silence if fewer than `n` are queued.
+    pub(crate) fn drain(&mut self, n: usize) -> Vec<i16> {
+        (0..n).map(|_| self.queue.pop_front().unwrap_or(0)).collect()
+    }
+}
@@ -1,6 +1,18 @@
 use std::time::Duration;
+use std::path::PathBuf;
 use chip8_base::{Interpreter, Pixel};
 
+mod recompiler;
+use recompiler::Recompiler;
+
+mod audio;
+use audio::AudioSynth;
+pub use audio::AudioConfig;
+
+mod rewind;
+use rewind::{RewindBuffer, REWIND_KEY};
+pub use rewind::RewindConfig;
+
 const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -25,6 +37,64 @@ const FONT_INDEX: usize = 0x50;
 
 static TIME_60HZ: Duration = Duration::from_nanos(16666667);
 
+// Magic bytes identifying a save-state file, and a version tag so we
+// never try to restore a layout we don't recognise.
+const STATE_MAGIC: [u8; 4] = *b"C8ST";
+const STATE_VERSION: u8 = 1;
+
+// Hold these two keys together to save/load a snapshot. They sit outside
+// the digits most ROMs bind to movement or selection, so they're free to
+// reuse as an emulator-level hotkey the way NES frontends reserve a
+// dedicated save/load button.
+const SAVE_STATE_KEYS: (usize, usize) = (0xC, 0xD);
+const LOAD_STATE_KEYS: (usize, usize) = (0xE, 0xF);
+
+/// Toggles for opcodes whose behaviour differs between CHIP-8 variants.
+/// Several ambiguous instructions were nailed down one way by the
+/// original COSMAC VIP interpreter, then done differently by later
+/// interpreters (e.g. SCHIP, XO-CHIP) that many modern ROMs target.
+/// Defaults match the classic COSMAC VIP.
+#[derive(Clone, Copy, Debug)]
+pub struct Quirks {
+    /// 8xy1/8xy2/8xy3 (OR/AND/XOR) reset VF to 0 afterwards.
+    pub vf_reset: bool,
+    /// 8xy6/8xyE shift Vx in place, rather than first copying Vy into Vx.
+    pub shift_in_place: bool,
+    /// Fx55/Fx65 advance I by x+1, rather than leaving it unchanged.
+    pub index_increment: bool,
+    /// Bnnn jumps to nnn + Vx (BXNN), rather than nnn + V0.
+    pub jump_with_vx: bool,
+    /// Dxyn clips sprites at the screen edge, rather than wrapping.
+    pub display_clip: bool,
+}
+
+/// Which execution strategy `VMState::step` uses to run instructions.
+/// `Interpreter` re-decodes every instruction through `execute`'s match;
+/// `Recompiler` pre-decodes straight-line runs into cached basic blocks
+/// (see `recompiler.rs`) so hot loops skip re-decoding entirely.
+pub enum Backend {
+    Interpreter,
+    Recompiler(Recompiler),
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Interpreter
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            vf_reset: true,
+            shift_in_place: false,
+            index_increment: true,
+            jump_with_vx: false,
+            display_clip: true,
+        }
+    }
+}
+
 pub struct VMState {
     memory: [u8; 4096],
     registers: [u8; 16],
@@ -38,10 +108,22 @@ pub struct VMState {
     decrement_timer: Duration,
     delay_timer: u8,
     sound_timer: u8,
+
+    rom_path: Option<PathBuf>,
+    quirks: Quirks,
+    backend: Backend,
+    audio: AudioSynth,
+    was_sounding: bool,
+    rewind: RewindBuffer,
+    // Previous tick's save/load hotkey combo state, so `step` acts on
+    // the press transition instead of re-triggering on every tick the
+    // combo is held.
+    save_key_held: bool,
+    load_key_held: bool,
 }
 
 impl VMState {
-    pub fn new(freq: u32) -> VMState {
+    pub fn new(freq: u32, quirks: Quirks) -> VMState {
         let s = 1_f64 / freq as f64;    // s per clock
 
         let mut m = [0; 4096];
@@ -60,6 +142,71 @@ impl VMState {
             decrement_timer: TIME_60HZ,
             delay_timer: 0,
             sound_timer: 0,
+            rom_path: None,
+            quirks,
+            backend: Backend::default(),
+            audio: AudioSynth::new(AudioConfig::default()),
+            was_sounding: false,
+            rewind: RewindBuffer::new(RewindConfig::default()),
+            save_key_held: false,
+            load_key_held: false,
+        }
+    }
+
+    /// Select the execution backend (the default is the plain
+    /// per-instruction interpreter; see `Backend`).
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// Drop any blocks the recompiler has decoded from `memory`. Needed
+    /// whenever `memory` is replaced wholesale rather than written a few
+    /// bytes at a time, since the recompiler otherwise only invalidates
+    /// blocks that overlap a specific Fx55/Fx33 write range.
+    fn invalidate_recompiler_cache(&mut self) {
+        if let Backend::Recompiler(recompiler) = &mut self.backend {
+            recompiler.invalidate_all();
+        }
+    }
+
+    /// Configure the buzzer's pitch and sample rate.
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        self.audio.set_config(config);
+    }
+
+    /// Configure how often the rewind buffer captures a frame and how
+    /// many frames it keeps.
+    pub fn set_rewind_config(&mut self, config: RewindConfig) {
+        self.rewind.set_config(config);
+    }
+
+    /// Pull up to `n` filtered square-wave samples for the host audio
+    /// layer to play, oldest first. The samples themselves are generated
+    /// continuously by `step` (see `tick_audio`), tied to the emulator's
+    /// own clock, rather than synthesized on demand here -- this just
+    /// drains whatever's queued.
+    pub fn audio_samples(&mut self, n: usize) -> Vec<i16> {
+        self.audio.drain(n)
+    }
+
+    /// Advance the buzzer by one tick's worth of filtered samples,
+    /// called once per `step` so the queue `audio_samples` drains fills
+    /// in step with emulated time whether or not a host ever drains it.
+    /// Resets the filters (and, with them, the startup ramp
+    /// `buzzer_active` waits out) whenever the buzzer restarts after
+    /// being silent, so one note doesn't bleed into the next.
+    fn tick_audio(&mut self) {
+        let active = self.sound_timer > 0;
+        if active && !self.was_sounding {
+            self.audio.reset();
+        }
+        self.was_sounding = active;
+
+        if active {
+            let samples_per_tick = (self.speed.as_secs_f64() * self.audio.sample_rate() as f64)
+                .round()
+                .max(1.0) as usize;
+            self.audio.generate(samples_per_tick);
         }
     }
 
@@ -69,22 +216,174 @@ impl VMState {
         }
     }
 
+    /// Remember where the ROM came from so save states can be written
+    /// alongside it (e.g. `mygame.ch8` -> `mygame-0.state`).
+    pub fn set_rom_path(&mut self, path: &str) {
+        self.rom_path = Some(PathBuf::from(path));
+    }
+
+    fn state_path(&self) -> PathBuf {
+        match &self.rom_path {
+            Some(p) => p.with_file_name(format!(
+                "{}-0.state",
+                p.file_stem().and_then(|s| s.to_str()).unwrap_or("game")
+            )),
+            None => PathBuf::from("game-0.state"),
+        }
+    }
+
+    /// Serialize the full machine snapshot to a fixed-layout byte dump.
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4200);
+        buf.extend_from_slice(&STATE_MAGIC);
+        buf.push(STATE_VERSION);
+        buf.extend_from_slice(&self.memory);
+        buf.extend_from_slice(&self.registers);
+        buf.extend_from_slice(&self.program_counter.to_le_bytes());
+        buf.push(self.stack_pointer);
+        for v in &self.stack {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.index.to_le_bytes());
+        for row in &self.display {
+            for px in row {
+                let on: bool = (*px).into();
+                buf.push(on as u8);
+            }
+        }
+        buf.push(self.delay_timer);
+        buf.push(self.sound_timer);
+        buf.extend_from_slice(&self.speed.as_nanos().to_le_bytes());
+        buf.extend_from_slice(&self.decrement_timer.as_nanos().to_le_bytes());
+        buf
+    }
+
+    /// Restore a machine snapshot produced by `serialize`, rejecting
+    /// anything that isn't ours or that would violate the invariants the
+    /// rest of `execute` relies on (e.g. an in-bounds `stack_pointer` and
+    /// `index`), since a corrupt file would otherwise panic on the
+    /// existing unchecked indexing.
+    fn deserialize(&mut self, buf: &[u8]) -> Result<(), &'static str> {
+        let mut cur = 0usize;
+        let mut take = |n: usize| -> Result<&[u8], &'static str> {
+            let slice = buf.get(cur..cur + n).ok_or("save state is truncated")?;
+            cur += n;
+            Ok(slice)
+        };
+
+        if take(4)? != STATE_MAGIC {
+            return Err("not a chip8 save state");
+        }
+        if take(1)?[0] != STATE_VERSION {
+            return Err("save state is from an incompatible version");
+        }
+
+        let memory: [u8; 4096] = take(4096)?.try_into().unwrap();
+        let registers: [u8; 16] = take(16)?.try_into().unwrap();
+        let program_counter = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        let stack_pointer = take(1)?[0];
+        if stack_pointer as usize >= 16 {
+            return Err("save state stack_pointer out of bounds");
+        }
+
+        let mut stack = [0u16; 16];
+        for slot in stack.iter_mut() {
+            *slot = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        }
+
+        let index = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        if index as usize >= memory.len() {
+            return Err("save state index out of bounds");
+        }
+
+        let mut display: chip8_base::Display = [[Pixel::default(); 64]; 32];
+        for row in display.iter_mut() {
+            for px in row.iter_mut() {
+                *px = take(1)?[0].try_into().map_err(|_| "invalid pixel byte")?;
+            }
+        }
+
+        let delay_timer = take(1)?[0];
+        let sound_timer = take(1)?[0];
+        let speed = Duration::from_nanos(u128::from_le_bytes(take(16)?.try_into().unwrap()) as u64);
+        let decrement_timer = Duration::from_nanos(u128::from_le_bytes(take(16)?.try_into().unwrap()) as u64);
+
+        self.memory = memory;
+        self.registers = registers;
+        self.program_counter = program_counter;
+        self.stack_pointer = stack_pointer;
+        self.stack = stack;
+        self.index = index;
+        self.display = display;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.speed = speed;
+        self.decrement_timer = decrement_timer;
+        self.invalidate_recompiler_cache();
+        rewind::invalidate(self);
+        Ok(())
+    }
+
+    /// Write a full machine snapshot to a `.state` file next to the ROM.
+    pub fn save_state(&self) -> std::io::Result<()> {
+        std::fs::write(self.state_path(), self.serialize())
+    }
+
+    /// Restore a machine snapshot previously written by `save_state`.
+    /// Corrupt or foreign files are rejected rather than applied.
+    pub fn load_state(&mut self) -> std::io::Result<()> {
+        let bytes = std::fs::read(self.state_path())?;
+        self.deserialize(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
     fn fetch(&mut self) -> (u8, u8) {
         let ind = self.program_counter as usize;
         (self.memory[ind], self.memory[ind+1])
     }
 
-    fn extract_nibbles(i: (u8, u8)) -> (u8, u8, u8, u8) {
+    pub(crate) fn extract_nibbles(i: (u8, u8)) -> (u8, u8, u8, u8) {
         return ((i.0 >> 4) & 0xf, i.0 & 0xf, (i.1 >> 4) & 0xf, i.1 & 0xf);
     }
 
-    fn extract_12_bits(n0: u8, n1: u8, n2: u8) -> u16 {
+    pub(crate) fn extract_12_bits(n0: u8, n1: u8, n2: u8) -> u16 {
         let p0 = (n0 as u16) << 8;
         let p1 = (n1 as u16) << 4;
         let p2 = n2 as u16;
         return p0 | p1 | p2;
     }
 
+    // Read-only accessors for the debugger (src/debugger.rs), which needs
+    // to inspect state that `execute` otherwise keeps private.
+    pub(crate) fn pc(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub(crate) fn sp(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    pub(crate) fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub(crate) fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub(crate) fn stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    /// Read up to `len` bytes starting at `start`, clamped to stay within
+    /// `memory`'s bounds (both `start` itself and the resulting slice
+    /// length) rather than trusting a caller-supplied address.
+    pub(crate) fn memory_range(&self, start: u16, len: u16) -> &[u8] {
+        let s = (start as usize).min(self.memory.len());
+        let len = (len as usize).min(self.memory.len() - s);
+        &self.memory[s..s + len]
+    }
+
     fn execute(&mut self, i: (u8, u8), keys: &chip8_base::Keys) -> Option<chip8_base::Display> {
         let (n0, n1, n2, n3) = VMState::extract_nibbles(i);
         match (n0, n1, n2, n3) {
@@ -136,18 +435,21 @@ impl VMState {
                 let vx = self.registers[x as usize];
                 let vy = self.registers[y as usize];
                 self.registers[x as usize] = vx | vy;
+                if self.quirks.vf_reset { self.registers[0xF] = 0; }
             },
             // 8xy2 AND Vx, Vy: Set Vx = Vx AND Vy.
             (0x8, x, y, 0x2) => {
                 let vx = self.registers[x as usize];
                 let vy = self.registers[y as usize];
                 self.registers[x as usize] = vx & vy;
+                if self.quirks.vf_reset { self.registers[0xF] = 0; }
             },
             // 8xy3 XOR Vx, Vy: Set Vx = Vx XOR Vy.
             (0x8, x, y, 0x3) => {
                 let vx = self.registers[x as usize];
                 let vy = self.registers[y as usize];
                 self.registers[x as usize] = vx ^ vy;
+                if self.quirks.vf_reset { self.registers[0xF] = 0; }
             },
             // 8xy4 ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry.
             (0x8, x, y, 0x4) => {
@@ -166,10 +468,14 @@ impl VMState {
                 self.registers[0xF as usize] = if borrow { 0 } else { 1 };
             },
             // 8xy6 SHR Vx {, Vy}: Set Vx = Vx SHR 1.
-            (0x8, x, _, 0x6) => {
-                let vx = self.registers[x as usize];
-                self.registers[0xF as usize] = vx & 0x1;   // LSB
-                self.registers[x as usize] = vx >> 1;
+            (0x8, x, y, 0x6) => {
+                let src = if self.quirks.shift_in_place {
+                    self.registers[x as usize]
+                } else {
+                    self.registers[y as usize]
+                };
+                self.registers[0xF as usize] = src & 0x1;   // LSB
+                self.registers[x as usize] = src >> 1;
             },
             // 8xy7 SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow.
             (0x8, x, y, 0x7) => {
@@ -180,10 +486,14 @@ impl VMState {
                 self.registers[0xF as usize] = if borrow { 0 } else { 1 };
             },
             // 8xyE SHL Vx {, Vy}: Set Vx = Vx SHL 1.
-            (0x8, x, _, 0xE) => {
-                let vx = self.registers[x as usize];
-                self.registers[0xF as usize] = vx & 0x80;   // MSB
-                self.registers[x as usize] = vx << 1;
+            (0x8, x, y, 0xE) => {
+                let src = if self.quirks.shift_in_place {
+                    self.registers[x as usize]
+                } else {
+                    self.registers[y as usize]
+                };
+                self.registers[0xF as usize] = (src & 0x80 != 0) as u8;   // MSB, normalized to 0/1
+                self.registers[x as usize] = src << 1;
             },
             // 9xy0 SNE Vx, Vy: Skip next instruction if Vx != Vy.
             (0x9, x, y, 0x0) => {
@@ -193,9 +503,10 @@ impl VMState {
             },
             // Annn LD I, addr: Set I = nnn.
             (0xA, n0, n1, n2) => self.index = VMState::extract_12_bits(n0, n1, n2),
-            // Bnnn JP V0, addr: Jump to location nnn + V0.
+            // Bnnn JP V0, addr: Jump to location nnn + V0 (or BXNN: xnn + Vx, if quirked).
             (0xB, n0, n1, n2) => {
-                self.program_counter = VMState::extract_12_bits(n0, n1, n2) + (self.registers[0] as u16);
+                let offset_reg = if self.quirks.jump_with_vx { n0 } else { 0 };
+                self.program_counter = VMState::extract_12_bits(n0, n1, n2) + (self.registers[offset_reg as usize] as u16);
             },
             // Cxkk RND Vx, byte: Set Vx = random byte AND kk.
             (0xC, x, _, _) => {
@@ -212,14 +523,16 @@ impl VMState {
                 for (i, row) in sprite.iter().enumerate() {
                     let pxy = tly + i as u8;
                     if pxy > 31 {
-                        break;
+                        if self.quirks.display_clip { break; }
                     }
-                    
+                    let pxy = pxy % 32;
+
                     for j in 0..8 {
                         let pxx = tlx + j;
                         if pxx > 63 {
-                            break;
+                            if self.quirks.display_clip { break; }
                         }
+                        let pxx = pxx % 64;
                         let old_px = &mut self.display[pxy as usize][pxx as usize];
                         let mask = 2_u8.pow(7 - j as u32);
                         let new_u8 = (row & mask) >> (7 - j);
@@ -299,6 +612,9 @@ impl VMState {
                 for i in 0..=end {
                     self.memory[ind + i] = self.registers[i];
                 }
+                if self.quirks.index_increment {
+                    self.index += end as u16 + 1;
+                }
             },
             // Fx65 LD Vx, [I]: Read registers V0 through Vx from memory starting at location I.
             (0xF, x, 0x6, 0x5) => {
@@ -307,6 +623,9 @@ impl VMState {
                 for i in 0..=end {
                     self.registers[i] = self.memory[ind + i];
                 }
+                if self.quirks.index_increment {
+                    self.index += end as u16 + 1;
+                }
             },
             _ => println!("Not implemented {} {} {} {}", n0, n1, n2, n3),
         }
@@ -316,7 +635,36 @@ impl VMState {
 
 impl Interpreter for VMState {
     fn step(&mut self, keys: &chip8_base::Keys) -> Option<chip8_base::Display> {
-        
+
+        // Reserved save/load hotkeys, piggy-backing on the keypad the
+        // same way `execute` reads `keys` for Ex9E/ExA1/Fx0A. Only acted
+        // on the press transition (not every tick the combo is held), so
+        // holding it down doesn't save/load on every single step.
+        let save_held = keys[SAVE_STATE_KEYS.0] && keys[SAVE_STATE_KEYS.1];
+        if save_held && !self.save_key_held {
+            if let Err(e) = self.save_state() {
+                println!("Failed to save state: {}", e);
+            }
+        }
+        self.save_key_held = save_held;
+
+        let load_held = keys[LOAD_STATE_KEYS.0] && keys[LOAD_STATE_KEYS.1];
+        if load_held && !self.load_key_held {
+            if let Err(e) = self.load_state() {
+                println!("Failed to load state: {}", e);
+            }
+        }
+        self.load_key_held = load_held;
+
+        // Reserved rewind key: hold it to pop captured frames and step
+        // backwards instead of forwards.
+        if keys[REWIND_KEY] {
+            if rewind::step_back(self) {
+                return Some(self.display);
+            }
+        }
+        rewind::maybe_capture(self);
+
         // Timers
         self.decrement_timer = self.decrement_timer.saturating_sub(self.speed);
         if self.decrement_timer == Duration::ZERO {
@@ -324,11 +672,22 @@ impl Interpreter for VMState {
             if self.sound_timer > 0 { self.sound_timer -= 1 }
             self.decrement_timer = TIME_60HZ;
         }
+        self.tick_audio();
 
-        let instr = self.fetch();
-        self.program_counter += 2;
-        self.program_counter %= 4096;
-        self.execute(instr, keys)
+        match std::mem::take(&mut self.backend) {
+            Backend::Interpreter => {
+                self.backend = Backend::Interpreter;
+                let instr = self.fetch();
+                self.program_counter += 2;
+                self.program_counter %= 4096;
+                self.execute(instr, keys)
+            }
+            Backend::Recompiler(mut recompiler) => {
+                let result = recompiler::step(self, &mut recompiler, keys);
+                self.backend = Backend::Recompiler(recompiler);
+                result
+            }
+        }
     }
 
     fn speed(&self) -> std::time::Duration {
@@ -336,8 +695,7 @@ impl Interpreter for VMState {
     }
 
     fn buzzer_active(&self) -> bool {
-        self.sound_timer > 0
-        // true
+        self.sound_timer > 0 && self.audio.is_ramped()
     }
 }
 
@@ -0,0 +1,407 @@
+// Basic-block recompiler backend: an alternative to `VMState::execute`'s
+// big per-instruction match. Instead of re-decoding every instruction on
+// every visit, memory starting at a given PC is decoded once into a
+// `Block` of pre-decoded `Op`s and cached by its entry PC. Re-entering a
+// cached block skips decoding entirely; only a write that could modify
+// cached bytes (Fx55/Fx33, which write `x+1`/3 bytes starting at `I`)
+// invalidates the blocks it overlaps.
+//
+// This lives as a submodule of `interpreter` (rather than its own
+// top-level module) so it can read and write `VMState`'s fields directly,
+// the same way `execute` does, instead of needing a second public
+// accessor for every field it touches.
+
+use std::collections::HashMap;
+use chip8_base::{Keys, Pixel};
+
+use super::VMState;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Op {
+    Cls,
+    LdVxByte(u8, u8),
+    AddVxByte(u8, u8),
+    LdVxVy(u8, u8),
+    OrVxVy(u8, u8),
+    AndVxVy(u8, u8),
+    XorVxVy(u8, u8),
+    AddVxVy(u8, u8),
+    SubVxVy(u8, u8),
+    ShrVx(u8, u8),
+    SubnVxVy(u8, u8),
+    ShlVx(u8, u8),
+    LdIAddr(u16),
+    RndVxByte(u8, u8),
+    LdVxDt(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdBVx(u8),   // Fx33, writes I..I+3
+    LdIVx(u8),   // Fx55, writes I..I+x+1
+    LdVxI(u8),   // Fx65, reads I..I+x+1
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Terminator {
+    Jump(u16),
+    Call(u16),
+    Ret,
+    JumpWithReg(u16, u8),        // Bnnn / BXNN; which register to add is resolved here by the quirk
+    SkipEqByte(u8, u8),
+    SkipNeqByte(u8, u8),
+    SkipEqReg(u8, u8),
+    SkipNeqReg(u8, u8),
+    SkipKeyPressed(u8),
+    SkipKeyNotPressed(u8),
+    WaitKey(u8),
+    Draw(u8, u8, u8),
+    Unimplemented,               // unknown opcode, handed to `VMState::execute`
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Block {
+    ops: Vec<Op>,
+    terminator: Terminator,
+    /// Total bytes spanned by the block, body ops plus the terminator
+    /// instruction, used both to advance the PC and to test overlap with
+    /// self-modified memory.
+    len_bytes: u16,
+}
+
+impl Block {
+    fn overlaps(&self, start_pc: u16, write_start: u16, write_end: u16) -> bool {
+        let block_end = start_pc + self.len_bytes;
+        write_start < block_end && start_pc < write_end
+    }
+}
+
+/// Cache of decoded basic blocks, keyed by entry PC, plus a cursor
+/// tracking where execution is within the block currently running so
+/// `step` can run exactly one instruction per call instead of the whole
+/// block at once (see `step` below).
+#[derive(Default)]
+pub struct Recompiler {
+    cache: HashMap<u16, Block>,
+    // (block entry PC, index into that block's `ops` the next `step`
+    // call should run; `ops.len()` means "run the terminator next").
+    active: Option<(u16, usize)>,
+}
+
+impl Recompiler {
+    fn invalidate_overlapping(&mut self, write_start: u16, write_end: u16) {
+        self.cache
+            .retain(|&start, block| !block.overlaps(start, write_start, write_end));
+    }
+
+    /// Drop every cached block. Called whenever `memory` is replaced
+    /// wholesale (save-state load, rewind) rather than incrementally
+    /// written, since `invalidate_overlapping` has no single write range
+    /// to compare against in that case.
+    pub(crate) fn invalidate_all(&mut self) {
+        self.cache.clear();
+        self.active = None;
+    }
+
+    fn decode_block(memory: &[u8; 4096], start: u16) -> Block {
+        let mut pc = start;
+        let mut ops = Vec::new();
+
+        loop {
+            let (b0, b1) = (memory[pc as usize], memory[(pc as usize + 1) % 4096]);
+            let (n0, n1, n2, n3) = VMState::extract_nibbles((b0, b1));
+            let nnn = VMState::extract_12_bits(n1, n2, n3);
+            let kk = b1;
+
+            macro_rules! terminate {
+                ($term:expr) => {{
+                    // Computed from the op count rather than `pc - start`
+                    // so it stays correct even when the scan below wraps
+                    // `pc` past the end of memory.
+                    let len_bytes = ops.len() as u16 * 2 + 2;
+                    return Block { ops, terminator: $term, len_bytes };
+                }};
+            }
+
+            match (n0, n1, n2, n3) {
+                (0x0, 0x0, 0xE, 0x0) => ops.push(Op::Cls),
+                (0x0, 0x0, 0xE, 0xE) => terminate!(Terminator::Ret),
+                (0x1, ..) => terminate!(Terminator::Jump(nnn)),
+                (0x2, ..) => terminate!(Terminator::Call(nnn)),
+                (0x3, x, ..) => terminate!(Terminator::SkipEqByte(x, kk)),
+                (0x4, x, ..) => terminate!(Terminator::SkipNeqByte(x, kk)),
+                (0x5, x, y, 0x0) => terminate!(Terminator::SkipEqReg(x, y)),
+                (0x6, x, ..) => ops.push(Op::LdVxByte(x, kk)),
+                (0x7, x, ..) => ops.push(Op::AddVxByte(x, kk)),
+                (0x8, x, y, 0x0) => ops.push(Op::LdVxVy(x, y)),
+                (0x8, x, y, 0x1) => ops.push(Op::OrVxVy(x, y)),
+                (0x8, x, y, 0x2) => ops.push(Op::AndVxVy(x, y)),
+                (0x8, x, y, 0x3) => ops.push(Op::XorVxVy(x, y)),
+                (0x8, x, y, 0x4) => ops.push(Op::AddVxVy(x, y)),
+                (0x8, x, y, 0x5) => ops.push(Op::SubVxVy(x, y)),
+                (0x8, x, y, 0x6) => ops.push(Op::ShrVx(x, y)),
+                (0x8, x, y, 0x7) => ops.push(Op::SubnVxVy(x, y)),
+                (0x8, x, y, 0xE) => ops.push(Op::ShlVx(x, y)),
+                (0x9, x, y, 0x0) => terminate!(Terminator::SkipNeqReg(x, y)),
+                (0xA, ..) => ops.push(Op::LdIAddr(nnn)),
+                (0xB, x, ..) => terminate!(Terminator::JumpWithReg(nnn, x)),
+                (0xC, x, ..) => ops.push(Op::RndVxByte(x, kk)),
+                (0xD, x, y, n) => terminate!(Terminator::Draw(x, y, n)),
+                (0xE, x, 0x9, 0xE) => terminate!(Terminator::SkipKeyPressed(x)),
+                (0xE, x, 0xA, 0x1) => terminate!(Terminator::SkipKeyNotPressed(x)),
+                (0xF, x, 0x0, 0x7) => ops.push(Op::LdVxDt(x)),
+                (0xF, x, 0x0, 0xA) => terminate!(Terminator::WaitKey(x)),
+                (0xF, x, 0x1, 0x5) => ops.push(Op::LdDtVx(x)),
+                (0xF, x, 0x1, 0x8) => ops.push(Op::LdStVx(x)),
+                (0xF, x, 0x1, 0xE) => ops.push(Op::AddIVx(x)),
+                (0xF, x, 0x2, 0x9) => ops.push(Op::LdFVx(x)),
+                (0xF, x, 0x3, 0x3) => ops.push(Op::LdBVx(x)),
+                (0xF, x, 0x5, 0x5) => ops.push(Op::LdIVx(x)),
+                (0xF, x, 0x6, 0x5) => ops.push(Op::LdVxI(x)),
+                // Unknown/unimplemented opcode: end the block on it and let
+                // `execute` run it, so its existing `_ => ...` diagnostic
+                // still fires.
+                _ => terminate!(Terminator::Unimplemented),
+            }
+
+            // Wrap like `VMState::step` does after fetching, so a block
+            // that scans into the top of memory doesn't index `memory`
+            // out of bounds.
+            pc = (pc + 2) % 4096;
+            // A runaway block (e.g. decoding straight into data) shouldn't
+            // grow without bound.
+            if ops.len() >= 512 {
+                terminate!(Terminator::Jump(pc));
+            }
+        }
+    }
+}
+
+fn apply_op(vm: &mut VMState, op: Op, recompiler: &mut Recompiler) {
+    match op {
+        Op::Cls => vm.display = [[Pixel::default(); 64]; 32],
+        Op::LdVxByte(x, kk) => vm.registers[x as usize] = kk,
+        Op::AddVxByte(x, kk) => {
+            vm.registers[x as usize] = vm.registers[x as usize].wrapping_add(kk)
+        }
+        Op::LdVxVy(x, y) => vm.registers[x as usize] = vm.registers[y as usize],
+        Op::OrVxVy(x, y) => {
+            vm.registers[x as usize] |= vm.registers[y as usize];
+            if vm.quirks.vf_reset { vm.registers[0xF] = 0; }
+        }
+        Op::AndVxVy(x, y) => {
+            vm.registers[x as usize] &= vm.registers[y as usize];
+            if vm.quirks.vf_reset { vm.registers[0xF] = 0; }
+        }
+        Op::XorVxVy(x, y) => {
+            vm.registers[x as usize] ^= vm.registers[y as usize];
+            if vm.quirks.vf_reset { vm.registers[0xF] = 0; }
+        }
+        Op::AddVxVy(x, y) => {
+            let (r, carry) = vm.registers[x as usize].overflowing_add(vm.registers[y as usize]);
+            vm.registers[x as usize] = r;
+            vm.registers[0xF] = carry as u8;
+        }
+        Op::SubVxVy(x, y) => {
+            let (r, borrow) = vm.registers[x as usize].overflowing_sub(vm.registers[y as usize]);
+            vm.registers[x as usize] = r;
+            vm.registers[0xF] = if borrow { 0 } else { 1 };
+        }
+        Op::ShrVx(x, y) => {
+            let src = if vm.quirks.shift_in_place { vm.registers[x as usize] } else { vm.registers[y as usize] };
+            vm.registers[0xF] = src & 0x1;
+            vm.registers[x as usize] = src >> 1;
+        }
+        Op::SubnVxVy(x, y) => {
+            let (r, borrow) = vm.registers[y as usize].overflowing_sub(vm.registers[x as usize]);
+            vm.registers[x as usize] = r;
+            vm.registers[0xF] = if borrow { 0 } else { 1 };
+        }
+        Op::ShlVx(x, y) => {
+            let src = if vm.quirks.shift_in_place { vm.registers[x as usize] } else { vm.registers[y as usize] };
+            vm.registers[0xF] = (src & 0x80 != 0) as u8;
+            vm.registers[x as usize] = src << 1;
+        }
+        Op::LdIAddr(nnn) => vm.index = nnn,
+        Op::RndVxByte(x, kk) => vm.registers[x as usize] = rand::random::<u8>() & kk,
+        Op::LdVxDt(x) => vm.registers[x as usize] = vm.delay_timer,
+        Op::LdDtVx(x) => vm.delay_timer = vm.registers[x as usize],
+        Op::LdStVx(x) => vm.sound_timer = vm.registers[x as usize],
+        Op::AddIVx(x) => {
+            let (r, carry) = vm.index.overflowing_add(vm.registers[x as usize].into());
+            vm.index = r;
+            vm.registers[0xF] = if carry || vm.index > 0x0FFF { 1 } else { 0 };
+        }
+        Op::LdFVx(x) => {
+            vm.index = super::FONT_INDEX as u16 + 5 * vm.registers[x as usize] as u16
+        }
+        Op::LdBVx(x) => {
+            let vx = vm.registers[x as usize];
+            let ind = vm.index as usize;
+            vm.memory[ind] = (vx / 100) % 10;
+            vm.memory[ind + 1] = (vx / 10) % 10;
+            vm.memory[ind + 2] = (vx / 1) % 10;
+            recompiler.invalidate_overlapping(vm.index, vm.index + 3);
+        }
+        Op::LdIVx(x) => {
+            let ind = vm.index as usize;
+            for i in 0..=x as usize {
+                vm.memory[ind + i] = vm.registers[i];
+            }
+            if vm.quirks.index_increment { vm.index += x as u16 + 1; }
+            recompiler.invalidate_overlapping(ind as u16, ind as u16 + x as u16 + 1);
+        }
+        Op::LdVxI(x) => {
+            let ind = vm.index as usize;
+            for i in 0..=x as usize {
+                vm.registers[i] = vm.memory[ind + i];
+            }
+            if vm.quirks.index_increment { vm.index += x as u16 + 1; }
+        }
+    }
+}
+
+/// Run the terminator that ended the current block, mirroring the
+/// equivalent arms of `VMState::execute`. `pc_after` is the address just past
+/// the terminator instruction (i.e. what `execute` sees after `step`'s
+/// pre-increment) before any jump/skip adjustment.
+fn apply_terminator(vm: &mut VMState, term: Terminator, pc_after: u16, keys: &Keys) -> Option<chip8_base::Display> {
+    match term {
+        Terminator::Jump(nnn) => vm.program_counter = nnn,
+        Terminator::Call(nnn) => {
+            vm.stack_pointer += 1;
+            vm.stack[vm.stack_pointer as usize] = pc_after;
+            vm.program_counter = nnn;
+        }
+        Terminator::Ret => {
+            vm.program_counter = vm.stack[vm.stack_pointer as usize];
+            vm.stack_pointer -= 1;
+        }
+        Terminator::JumpWithReg(nnn, x) => {
+            let reg = if vm.quirks.jump_with_vx { x } else { 0 };
+            vm.program_counter = nnn + vm.registers[reg as usize] as u16;
+        }
+        Terminator::SkipEqByte(x, kk) => {
+            vm.program_counter = pc_after + if vm.registers[x as usize] == kk { 2 } else { 0 };
+        }
+        Terminator::SkipNeqByte(x, kk) => {
+            vm.program_counter = pc_after + if vm.registers[x as usize] != kk { 2 } else { 0 };
+        }
+        Terminator::SkipEqReg(x, y) => {
+            vm.program_counter = pc_after + if vm.registers[x as usize] == vm.registers[y as usize] { 2 } else { 0 };
+        }
+        Terminator::SkipNeqReg(x, y) => {
+            vm.program_counter = pc_after + if vm.registers[x as usize] != vm.registers[y as usize] { 2 } else { 0 };
+        }
+        Terminator::SkipKeyPressed(x) => {
+            let vx = vm.registers[x as usize];
+            vm.program_counter = pc_after + if keys[vx as usize] { 2 } else { 0 };
+        }
+        Terminator::SkipKeyNotPressed(x) => {
+            let vx = vm.registers[x as usize];
+            vm.program_counter = pc_after + if !keys[vx as usize] { 2 } else { 0 };
+        }
+        Terminator::WaitKey(x) => {
+            if !keys.iter().any(|k| *k) {
+                vm.program_counter = pc_after - 2;
+            } else {
+                vm.program_counter = pc_after;
+                for (i, k) in keys.iter().enumerate() {
+                    if *k {
+                        vm.registers[x as usize] = i as u8;
+                        break;
+                    }
+                }
+            }
+        }
+        Terminator::Draw(x, y, n) => {
+            vm.program_counter = pc_after;
+            let tlx = vm.registers[x as usize] % 64;
+            let tly = vm.registers[y as usize] % 32;
+            vm.registers[0xF] = 0;
+            let ind = vm.index as usize;
+            let sprite: Vec<u8> = vm.memory[ind..(ind + n as usize)].to_vec();
+
+            for (i, row) in sprite.iter().enumerate() {
+                let pxy = tly + i as u8;
+                if pxy > 31 && vm.quirks.display_clip { break; }
+                let pxy = pxy % 32;
+
+                for j in 0..8 {
+                    let pxx = tlx + j;
+                    if pxx > 63 && vm.quirks.display_clip { break; }
+                    let pxx = pxx % 64;
+                    let old_px = &mut vm.display[pxy as usize][pxx as usize];
+                    let mask = 2_u8.pow(7 - j as u32);
+                    let new_u8 = (row & mask) >> (7 - j);
+                    let new_px: Pixel = new_u8.try_into().unwrap();
+                    if (new_px & *old_px).into() {
+                        vm.registers[0xF] = 1;
+                    }
+                    *old_px ^= new_px;
+                }
+            }
+            return Some(vm.display);
+        }
+        Terminator::Unimplemented => {
+            let at = (pc_after as usize - 2) % 4096;
+            let instr = (vm.memory[at], vm.memory[(at + 1) % 4096]);
+            vm.program_counter = pc_after;
+            return vm.execute(instr, keys);
+        }
+    }
+    None
+}
+
+/// Entry point used by `VMState::step` when the recompiler backend is
+/// selected. Executes exactly one CHIP-8 instruction per call, the same
+/// as `Backend::Interpreter`'s `fetch`+`execute` -- the cached `Block`
+/// only lets repeated visits to the same straight-line run skip
+/// re-decoding; it's never run start-to-finish in a single call, since
+/// that would advance several instructions per host tick and decouple
+/// the emulator's actual clock rate from `--freq`.
+pub(crate) fn step(vm: &mut VMState, recompiler: &mut Recompiler, keys: &Keys) -> Option<chip8_base::Display> {
+    let pc = vm.program_counter;
+
+    // Resume the in-flight block only if `pc` is exactly where the last
+    // call left off and that block is still cached (a self-modifying
+    // write may have invalidated it since); otherwise decode (or fetch
+    // the cached decode of) the block for this fresh entry point.
+    let resuming = matches!(recompiler.active, Some((start, idx)) if
+        (start + idx as u16 * 2) % 4096 == pc && recompiler.cache.contains_key(&start));
+
+    let (start, idx) = if resuming {
+        recompiler.active.unwrap()
+    } else {
+        if !recompiler.cache.contains_key(&pc) {
+            let block = Recompiler::decode_block(&vm.memory, pc);
+            recompiler.cache.insert(pc, block);
+        }
+        (pc, 0)
+    };
+
+    // `Op`/`Terminator` are both `Copy`, so pull out just the one value
+    // this call needs rather than cloning the whole `Block` (its `Vec`
+    // of ops) on every single-instruction step.
+    let ops_len = recompiler.cache.get(&start).unwrap().ops.len();
+
+    if idx < ops_len {
+        let op = recompiler.cache.get(&start).unwrap().ops[idx];
+        let display_dirty = matches!(op, Op::Cls);
+        apply_op(vm, op, recompiler);
+        vm.program_counter = (start + (idx as u16 + 1) * 2) % 4096;
+        recompiler.active = Some((start, idx + 1));
+        return if display_dirty { Some(vm.display) } else { None };
+    }
+
+    // `idx == ops_len`: every body op has run, so this call resolves the
+    // terminator, just as `execute` would for the corresponding
+    // control-flow instruction.
+    let block = recompiler.cache.get(&start).unwrap();
+    let (terminator, pc_after) = (block.terminator, start + block.len_bytes);
+    let drawn = apply_terminator(vm, terminator, pc_after, keys);
+    vm.program_counter %= 4096;
+    recompiler.active = None;
+
+    drawn
+}
@@ -0,0 +1,220 @@
+// Frame-by-frame rewind, built on the same full-state fields
+// `save_state`/`load_state` (see the parent module, `mod.rs`) already know
+// how to snapshot. Rather than keep a full 4 KB memory + display copy
+// per captured frame, each frame stores only what changed since the
+// previous capture -- a changed-byte list for memory and a changed-cell
+// list for the display -- since most bytes are unchanged between two
+// captures a few instructions apart. The ring buffer is capacity-bounded
+// and drops its oldest frame once full.
+//
+// Lives as a submodule of `interpreter` so it can read and restore
+// `VMState`'s fields directly, the same way `recompiler` does.
+
+use std::collections::VecDeque;
+use chip8_base::{Display, Pixel};
+
+use super::VMState;
+
+/// How often to capture a frame, and how many frames to keep.
+#[derive(Clone, Copy, Debug)]
+pub struct RewindConfig {
+    /// Capture a frame every this many executed instructions.
+    pub capture_interval: u32,
+    /// Maximum number of frames kept before the oldest is dropped.
+    pub capacity: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig { capture_interval: 10, capacity: 600 }
+    }
+}
+
+/// Hold this key to pop frames off the rewind buffer and step the
+/// machine backwards, one frame per tick, instead of forwards.
+pub(crate) const REWIND_KEY: usize = 0xB;
+
+struct Frame {
+    mem_diff: Vec<(u16, u8)>,
+    disp_diff: Vec<(u8, u8, Pixel)>,
+    program_counter: u16,
+    stack_pointer: u8,
+    stack: [u16; 16],
+    index: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+pub(crate) struct RewindBuffer {
+    config: RewindConfig,
+    frames: VecDeque<Frame>,
+    since_last_capture: u32,
+    // The state as of the last capture (or load), used to diff against
+    // when the next capture is due.
+    baseline_memory: [u8; 4096],
+    baseline_display: Display,
+    baseline_pc: u16,
+    baseline_sp: u8,
+    baseline_stack: [u16; 16],
+    baseline_index: u16,
+    baseline_delay: u8,
+    baseline_sound: u8,
+    primed: bool,
+}
+
+impl RewindBuffer {
+    pub(crate) fn new(config: RewindConfig) -> RewindBuffer {
+        RewindBuffer {
+            config,
+            frames: VecDeque::new(),
+            since_last_capture: 0,
+            baseline_memory: [0; 4096],
+            baseline_display: [[Pixel::default(); 64]; 32],
+            baseline_pc: 0,
+            baseline_sp: 0,
+            baseline_stack: [0; 16],
+            baseline_index: 0,
+            baseline_delay: 0,
+            baseline_sound: 0,
+            primed: false,
+        }
+    }
+
+    pub(crate) fn set_config(&mut self, config: RewindConfig) {
+        self.config = config;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_baseline(
+        &mut self,
+        memory: [u8; 4096],
+        display: Display,
+        pc: u16,
+        sp: u8,
+        stack: [u16; 16],
+        index: u16,
+        delay: u8,
+        sound: u8,
+    ) {
+        self.baseline_memory = memory;
+        self.baseline_display = display;
+        self.baseline_pc = pc;
+        self.baseline_sp = sp;
+        self.baseline_stack = stack;
+        self.baseline_index = index;
+        self.baseline_delay = delay;
+        self.baseline_sound = sound;
+    }
+}
+
+/// Snapshot the scalar/array fields `RewindBuffer` needs out of `vm` and
+/// hand them to `set_baseline`, avoiding holding a borrow of `vm` while
+/// `vm.rewind` is borrowed mutably.
+fn rebaseline(vm: &mut VMState) {
+    let (memory, display, pc, sp, stack, index, delay, sound) = (
+        vm.memory,
+        vm.display,
+        vm.program_counter,
+        vm.stack_pointer,
+        vm.stack,
+        vm.index,
+        vm.delay_timer,
+        vm.sound_timer,
+    );
+    vm.rewind.set_baseline(memory, display, pc, sp, stack, index, delay, sound);
+}
+
+/// Drop every captured frame and resync the baseline to `vm`'s current
+/// state. Needed whenever state is replaced wholesale (e.g.
+/// `VMState::deserialize`) rather than by normal execution -- otherwise
+/// old frames would diff the restored state against a stale pre-load
+/// baseline, and a later rewind could splice bytes from the pre-load
+/// timeline onto it.
+pub(crate) fn invalidate(vm: &mut VMState) {
+    vm.rewind.frames.clear();
+    vm.rewind.since_last_capture = 0;
+    rebaseline(vm);
+}
+
+/// Called once per executed instruction; captures a frame every
+/// `capture_interval` calls.
+pub(crate) fn maybe_capture(vm: &mut VMState) {
+    if !vm.rewind.primed {
+        vm.rewind.primed = true;
+        rebaseline(vm);
+        return;
+    }
+
+    vm.rewind.since_last_capture += 1;
+    if vm.rewind.since_last_capture < vm.rewind.config.capture_interval {
+        return;
+    }
+    vm.rewind.since_last_capture = 0;
+
+    let mem_diff: Vec<(u16, u8)> = vm
+        .rewind
+        .baseline_memory
+        .iter()
+        .zip(vm.memory.iter())
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(addr, (old, _))| (addr as u16, *old))
+        .collect();
+
+    let mut disp_diff = Vec::new();
+    for (r, (old_row, new_row)) in vm.rewind.baseline_display.iter().zip(vm.display.iter()).enumerate() {
+        for (c, (old_px, new_px)) in old_row.iter().zip(new_row.iter()).enumerate() {
+            if old_px != new_px {
+                disp_diff.push((r as u8, c as u8, *old_px));
+            }
+        }
+    }
+
+    let frame = Frame {
+        mem_diff,
+        disp_diff,
+        program_counter: vm.rewind.baseline_pc,
+        stack_pointer: vm.rewind.baseline_sp,
+        stack: vm.rewind.baseline_stack,
+        index: vm.rewind.baseline_index,
+        delay_timer: vm.rewind.baseline_delay,
+        sound_timer: vm.rewind.baseline_sound,
+    };
+
+    if vm.rewind.frames.len() >= vm.rewind.config.capacity {
+        vm.rewind.frames.pop_front();
+    }
+    vm.rewind.frames.push_back(frame);
+    rebaseline(vm);
+}
+
+/// Pop the most recent captured frame and restore it, returning whether
+/// a frame was available to rewind to.
+pub(crate) fn step_back(vm: &mut VMState) -> bool {
+    let frame = match vm.rewind.frames.pop_back() {
+        Some(f) => f,
+        None => return false,
+    };
+
+    for (addr, old) in &frame.mem_diff {
+        vm.memory[*addr as usize] = *old;
+    }
+    for (r, c, old_px) in &frame.disp_diff {
+        vm.display[*r as usize][*c as usize] = *old_px;
+    }
+    vm.program_counter = frame.program_counter;
+    vm.stack_pointer = frame.stack_pointer;
+    vm.stack = frame.stack;
+    vm.index = frame.index;
+    vm.delay_timer = frame.delay_timer;
+    vm.sound_timer = frame.sound_timer;
+
+    // `memory` was just overwritten wholesale, so any blocks the
+    // recompiler had decoded from it may no longer match.
+    vm.invalidate_recompiler_cache();
+
+    // The restored state becomes the new baseline so a subsequent
+    // capture (or rewind) diffs against where we actually are now.
+    rebaseline(vm);
+    true
+}
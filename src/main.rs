@@ -1,17 +1,42 @@
+mod debugger;
 mod interpreter;
 
 use clap::Parser;
+use debugger::Debugger;
+use interpreter::{AudioConfig, Backend, Quirks, RewindConfig};
 
 fn main() {
     let cli = Cli::parse();
 
     let filename: &str = &cli.rom;
-    let mut state = interpreter::VMState::new(cli.freq);
+    let quirks = Quirks {
+        vf_reset: cli.vf_reset,
+        shift_in_place: cli.shift_in_place,
+        index_increment: cli.index_increment,
+        jump_with_vx: cli.jump_with_vx,
+        display_clip: cli.display_clip,
+    };
+    let mut state = interpreter::VMState::new(cli.freq, quirks);
 
     let rom = std::fs::read(filename).expect("ROM file doesn't exist");
     state.load(&rom);
+    state.set_rom_path(filename);
 
-    chip8_base::run(state);
+    if cli.recompiler {
+        state.set_backend(Backend::Recompiler(Default::default()));
+    }
+
+    state.set_audio_config(AudioConfig { pitch_hz: cli.pitch, ..Default::default() });
+    state.set_rewind_config(RewindConfig {
+        capture_interval: cli.rewind_interval,
+        ..Default::default()
+    });
+
+    if cli.debug {
+        chip8_base::run(Debugger::new(state, cli.trace));
+    } else {
+        chip8_base::run(state);
+    }
 }
 
 #[derive(Parser)]
@@ -23,6 +48,42 @@ struct Cli {
     // Frequency to run the interpreter at
     #[clap(action, default_value_t = 700)]
     freq: u32,
+
+    /// 8xy1/8xy2/8xy3 (OR/AND/XOR) reset VF to 0 afterwards
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    vf_reset: bool,
+    /// 8xy6/8xyE shift Vx in place, rather than first copying Vy into Vx
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = false)]
+    shift_in_place: bool,
+    /// Fx55/Fx65 advance I by x+1, rather than leaving it unchanged
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    index_increment: bool,
+    /// Bnnn jumps to nnn + Vx (BXNN), rather than nnn + V0
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = false)]
+    jump_with_vx: bool,
+    /// Dxyn clips sprites at the screen edge, rather than wrapping
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    display_clip: bool,
+
+    /// Drop into an interactive stepping debugger before each instruction
+    #[clap(long, action)]
+    debug: bool,
+    /// With --debug, print every executed instruction instead of halting
+    #[clap(long, action)]
+    trace: bool,
+
+    /// Use the basic-block recompiler backend instead of the plain
+    /// per-instruction interpreter (helps throughput at high --freq)
+    #[clap(long, action)]
+    recompiler: bool,
+
+    /// Pitch of the synthesized buzzer tone, in Hz
+    #[clap(long, action, default_value_t = 440.0)]
+    pitch: f64,
+
+    /// Capture a rewind frame every this many executed instructions
+    #[clap(long, action, default_value_t = 10)]
+    rewind_interval: u32,
 }
 
 fn rom_exists(f: &str) -> Result<(), &'static str> {